@@ -1,21 +1,75 @@
 use serde::{Deserialize, Serialize};
 use serde_yaml;
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
+/// A single deployment unit: where its binary lives, how to update it, and
+/// how to run it.
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Config {
-    pub program_name: String,
+pub struct ProgramSpec {
     pub program_path: PathBuf,
     pub update: Update,
     pub run: Run,
+    /// directory used to persist cross-run state (tracked-path digests and
+    /// pre-update backups); defaults to `/var/lib/deploy-helper/<program_name>`
+    #[serde(default)]
+    pub state_directory: Option<PathBuf>,
+    /// whether to back up tracked paths before updating and verify the run
+    /// service comes up healthy afterward, rolling back otherwise
+    #[serde(default = "default_health_check_enabled")]
+    pub health_check_enabled: bool,
+    /// in seconds; how long to wait for the run service to become active
+    /// before rolling back to the pre-update backup
+    #[serde(default = "default_health_timeout")]
+    pub health_timeout: u64,
+}
+
+impl ProgramSpec {
+    /// The state directory to use, falling back to the default location
+    /// derived from `program_name` when none is configured.
+    pub fn state_dir(&self, program_name: &str) -> PathBuf {
+        self.state_directory
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("/var/lib/deploy-helper").join(program_name))
+    }
+}
+
+fn default_health_check_enabled() -> bool {
+    true
+}
+
+fn default_health_timeout() -> u64 {
+    30
 }
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Update {
     /// in seconds
     pub interval: u32,
     /// list of bash commands; each String must be runnable in bash
     pub commands: Vec<String>,
+    /// how many times to retry a failing update command before giving up
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// in seconds; the retry delay before jitter, doubled on each attempt
+    #[serde(default = "default_base_delay")]
+    pub base_delay: u64,
+    /// in seconds; the upper bound the doubling delay is capped at
+    #[serde(default = "default_max_delay")]
+    pub max_delay: u64,
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_base_delay() -> u64 {
+    1
+}
+
+fn default_max_delay() -> u64 {
+    30
 }
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Run {
@@ -23,12 +77,141 @@ pub struct Run {
     pub commands: Vec<String>,
 }
 
+/// A deploy-helper config describes one or more named deployment units.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub programs: HashMap<String, ProgramSpec>,
+}
+
+/// The on-disk YAML shape, which may either be the current multi-program
+/// map or the legacy single-program layout (a bare `program_name` alongside
+/// the rest of a `ProgramSpec`'s fields).
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawConfig {
+    Multi {
+        programs: HashMap<String, ProgramSpec>,
+    },
+    Single {
+        program_name: String,
+        #[serde(flatten)]
+        spec: ProgramSpec,
+    },
+}
+
 pub fn parse_config(path: &PathBuf) -> Config {
     // Read the entire file to a string
     let contents = fs::read_to_string(&path)
         .unwrap_or_else(|e| panic!("Failed to read config file {:?}: {}", path, e));
 
-    // Parse the YAML into your Config struct
-    serde_yaml::from_str(&contents)
-        .unwrap_or_else(|e| panic!("Failed to parse YAML in {:?}: {}", path, e))
+    // Parse the YAML into the raw shape, then normalize into a program map
+    let raw: RawConfig = serde_yaml::from_str(&contents)
+        .unwrap_or_else(|e| panic!("Failed to parse YAML in {:?}: {}", path, e));
+
+    let programs = match raw {
+        RawConfig::Multi { programs } => programs,
+        RawConfig::Single { program_name, spec } => {
+            let mut programs = HashMap::new();
+            programs.insert(program_name, spec);
+            programs
+        }
+    };
+
+    Config { programs }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str, tag: &str) -> Config {
+        let path = std::env::temp_dir().join(format!(
+            "deploy-helper-test-config-{}-{}.yaml",
+            std::process::id(),
+            tag
+        ));
+        fs::write(&path, yaml).unwrap();
+        let config = parse_config(&path);
+        let _ = fs::remove_file(&path);
+        config
+    }
+
+    #[test]
+    fn parses_legacy_single_program_shape_into_a_one_entry_map() {
+        let config = parse(
+            r#"
+program_name: geph
+program_path: /usr/bin/geph
+update:
+  interval: 60
+  commands: ["git pull"]
+run:
+  commands: ["./geph"]
+"#,
+            "legacy",
+        );
+
+        assert_eq!(config.programs.len(), 1);
+        let spec = &config.programs["geph"];
+        assert_eq!(spec.program_path, PathBuf::from("/usr/bin/geph"));
+        assert_eq!(spec.update.commands, vec!["git pull".to_string()]);
+        assert_eq!(spec.run.commands, vec!["./geph".to_string()]);
+    }
+
+    #[test]
+    fn parses_multi_program_shape() {
+        let config = parse(
+            r#"
+programs:
+  geph:
+    program_path: /usr/bin/geph
+    update:
+      interval: 60
+      commands: ["git pull"]
+    run:
+      commands: ["./geph"]
+  sosistab:
+    program_path: /usr/bin/sosistab
+    update:
+      interval: 120
+      commands: ["cargo build"]
+    run:
+      commands: ["./sosistab"]
+"#,
+            "multi",
+        );
+
+        assert_eq!(config.programs.len(), 2);
+        assert_eq!(
+            config.programs["geph"].program_path,
+            PathBuf::from("/usr/bin/geph")
+        );
+        assert_eq!(
+            config.programs["sosistab"].program_path,
+            PathBuf::from("/usr/bin/sosistab")
+        );
+    }
+
+    #[test]
+    fn applies_defaults_for_retry_and_health_fields() {
+        let config = parse(
+            r#"
+program_name: geph
+program_path: /usr/bin/geph
+update:
+  interval: 60
+  commands: ["git pull"]
+run:
+  commands: ["./geph"]
+"#,
+            "defaults",
+        );
+
+        let spec = &config.programs["geph"];
+        assert_eq!(spec.update.max_retries, default_max_retries());
+        assert_eq!(spec.update.base_delay, default_base_delay());
+        assert_eq!(spec.update.max_delay, default_max_delay());
+        assert_eq!(spec.health_check_enabled, default_health_check_enabled());
+        assert_eq!(spec.health_timeout, default_health_timeout());
+    }
 }
@@ -0,0 +1,56 @@
+use anyhow::{Context, Result};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// How long `run_svc` must report active, without interruption, before
+/// we're willing to call it healthy. A `Type=simple` unit reports "active"
+/// as soon as the process is forked, before it's done any work, so a single
+/// `is-active` success doesn't rule out a binary that execs and crashes a
+/// moment later.
+const SETTLE: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+fn is_failed(run_svc: &str) -> Result<bool> {
+    Command::new("systemctl")
+        .args(["is-failed", run_svc])
+        .status()
+        .context("failed to invoke systemctl is-failed")
+        .map(|status| status.success())
+}
+
+fn is_active(run_svc: &str) -> Result<bool> {
+    Command::new("systemctl")
+        .args(["is-active", run_svc])
+        .status()
+        .context("failed to invoke systemctl is-active")
+        .map(|status| status.success())
+}
+
+/// Poll `systemctl is-failed`/`is-active` for `run_svc` until it's been
+/// continuously active for `SETTLE`, reported failed, or `timeout` elapses.
+/// Returns whether it came up and stayed up healthy.
+pub fn wait_until_healthy(run_svc: &str, timeout: Duration) -> Result<bool> {
+    let deadline = Instant::now() + timeout;
+    let mut active_since: Option<Instant> = None;
+
+    loop {
+        if is_failed(run_svc)? {
+            return Ok(false);
+        }
+
+        if is_active(run_svc)? {
+            let since = *active_since.get_or_insert_with(Instant::now);
+            if since.elapsed() >= SETTLE {
+                return Ok(true);
+            }
+        } else {
+            active_since = None;
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(false);
+        }
+        std::thread::sleep(remaining.min(POLL_INTERVAL));
+    }
+}
@@ -1,19 +1,26 @@
 use std::{
+    collections::HashMap,
     env,
     fs::File,
     io::Write,
     path::{Path, PathBuf},
     process::Command,
+    time::Duration,
 };
 
-use crate::config::{Config, parse_config};
+use crate::config::{Config, ProgramSpec, parse_config};
 use anyhow::Context;
 use atomicwrites::{AtomicFile, OverwriteBehavior::AllowOverwrite};
-use clap::{Parser, Subcommand, builder::PathBufValueParser};
+use clap::{Parser, Subcommand};
 use fs2::FileExt;
 use once_cell::sync::Lazy;
 
 mod config;
+mod health;
+mod plan;
+mod run_commands;
+mod state;
+mod units;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -27,7 +34,13 @@ enum Commands {
     /// execute repo update commands in config
     Update { config: PathBuf },
     /// execute run commands
-    Run { config: PathBuf },
+    Run {
+        config: PathBuf,
+        /// which program to run; required when the config has more than one
+        program: Option<String>,
+    },
+    /// print the actions `update` would take, as JSON, without taking them
+    Plan { config: PathBuf },
 }
 
 static ARGS: Lazy<Args> = Lazy::new(Args::parse);
@@ -40,38 +53,63 @@ fn main() {
 
     if let Err(e) = match &ARGS.command {
         Commands::Update { config } => update(config),
-        Commands::Run { config } => run(config),
+        Commands::Run { config, program } => run(config, program),
+        Commands::Plan { config } => plan_cmd(config),
     } {
         log::error!("ERROR: {e}");
     };
 }
 
-fn run(config_path: &PathBuf) -> anyhow::Result<()> {
+/// Pick the program a `run` invocation should act on: the explicitly named
+/// one, or the sole entry when the config has only one.
+fn select_program<'a>(
+    config: &'a Config,
+    program: &Option<String>,
+) -> anyhow::Result<(&'a String, &'a ProgramSpec)> {
+    match program {
+        Some(name) => config
+            .programs
+            .get_key_value(name)
+            .with_context(|| format!("no program named `{}` in config", name)),
+        None => match config.programs.len() {
+            1 => Ok(config.programs.iter().next().unwrap()),
+            0 => anyhow::bail!("config defines no programs"),
+            _ => anyhow::bail!("config defines multiple programs; specify which one to run"),
+        },
+    }
+}
+
+fn run(config_path: &PathBuf, program: &Option<String>) -> anyhow::Result<()> {
     let config = parse_config(config_path);
     let config_dir = config_path
         .parent()
         .context("config has no parent directory")?;
     env::set_current_dir(config_dir)?;
 
-    for cmd in &config.run.commands {
-        log::debug!("Running: {}", cmd);
-        let status = Command::new("bash")
-            .arg("-c")
-            .arg(cmd)
-            .status()
-            .unwrap_or_else(|e| panic!("Failed to spawn bash for `{}`: {}", cmd, e));
-
-        if !status.success() {
-            anyhow::bail!("Command `{}` exited with status {}", cmd, status);
-        }
-    }
+    let (_, spec) = select_program(&config, program)?;
+    run_commands::run_commands(&spec.run.commands)
+}
+
+/// Print, as JSON, exactly what `update` would do for this config, without
+/// running any update command or touching `/etc/systemd/system`.
+fn plan_cmd(config_path: &PathBuf) -> anyhow::Result<()> {
+    let config = parse_config(config_path);
+    let deploy_helper_exe = env::current_exe()?;
+    let the_plan = plan::build(config_path, &deploy_helper_exe, &config);
+    println!("{}", serde_json::to_string_pretty(&the_plan)?);
     Ok(())
 }
 
-/// Perform the update commands, (re)generate systemd units, and activate them.
+/// For each program in the config: run its update commands, (re)generate its
+/// systemd units, then reload systemd once, enable each program's update
+/// timer, and restart each run service whose tracked paths changed.
 fn update(config_path: &PathBuf) -> anyhow::Result<()> {
     let config = parse_config(config_path);
-    let lock_path = format!("/var/lock/update-{}.lock", config.program_name);
+    let lock_name = config_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .context("config path has no file name")?;
+    let lock_path = format!("/var/lock/update-{}.lock", lock_name);
     let lock_file = File::create(Path::new(&lock_path))?;
     if let Err(e) = lock_file.try_lock_exclusive() {
         log::error!("Another instance is already running: {}", e);
@@ -87,115 +125,131 @@ fn update(config_path: &PathBuf) -> anyhow::Result<()> {
 
     log::debug!("2 - set cwd to {}", config_dir.display());
 
-    // 1. Immediate update – run all commands
-    for cmd in &config.update.commands {
-        let status = Command::new("bash").arg("-c").arg(cmd).status()?;
-        if !status.success() {
-            anyhow::bail!("`{}` exited with {}", cmd, status);
-        }
-    }
-
-    log::debug!("3 - all update commands executed");
-
-    // 2. Names & paths
     let deploy_helper_exe = env::current_exe()?;
-    let program_name = config.program_name;
-    let update_svc = format!("update-{}.service", program_name);
-    let update_timer = format!("update-{}.timer", program_name);
-    let run_svc = format!("run-{}.service", program_name);
     let sysd_dir = PathBuf::from("/etc/systemd/system");
 
-    // 3a. Update service
-    let update_unit = format!(
-        r#"[Unit]
-Description=deploy-helper update for {program_name}
-Wants={run_svc}
-After=network-online.target
-
-[Service]
-Type=oneshot 
-ExecStart={deploy_helper_exe} update {config_path}
-"#,
-        run_svc = run_svc,
-        deploy_helper_exe = deploy_helper_exe.display(),
-        config_path = config_path.display(),
-    );
+    let mut backups_by_program = HashMap::new();
+    for (program_name, spec) in &config.programs {
+        let backups = update_program(config_path, &deploy_helper_exe, &sysd_dir, program_name, spec)?;
+        backups_by_program.insert(program_name.clone(), backups);
+    }
 
-    // 3b. Timer unit
-    let timer_unit = format!(
-        r#"[Unit]
-Description=deploy-helper update timer for {program_name}
-
-[Timer]
-OnBootSec=1min
-OnUnitActiveSec={interval}
-Unit={update_svc}
-
-[Install]
-WantedBy=timers.target
-"#,
-        interval = config.update.interval,
-        update_svc = update_svc,
-    );
+    log::debug!("3 - all programs updated and unit files written");
 
-    // 3c. Run service
-    let run_unit = format!(
-        r#"[Unit]
-Description=deploy-helper run for {program_name}
-
-[Service]
-Type=simple 
-ExecStart={deploy_helper_exe} run {config_path}
-Restart=on-failure
-RestartSec=5
-
-[Install]
-WantedBy=multi-user.target
-"#,
-        deploy_helper_exe = deploy_helper_exe.display(),
-        config_path = config_path.display(),
-    );
+    // Reload once after all unit files for all programs have been written.
+    Command::new("systemctl").arg("daemon-reload").status()?;
 
-    // 4. Write unit files
-    AtomicFile::new(&sysd_dir.join(&update_svc), AllowOverwrite)
-        .write(|f| f.write_all(update_unit.as_bytes()))?;
-    AtomicFile::new(&sysd_dir.join(&update_timer), AllowOverwrite)
-        .write(|f| f.write_all(timer_unit.as_bytes()))?;
-    AtomicFile::new(&sysd_dir.join(&run_svc), AllowOverwrite)
-        .write(|f| f.write_all(run_unit.as_bytes()))?;
+    for (program_name, spec) in &config.programs {
+        let update_timer = format!("update-{}.timer", program_name);
+        let run_svc = format!("run-{}.service", program_name);
+        Command::new("systemctl")
+            .args(["enable", "--now", &update_timer])
+            .status()?;
+        restart_if_changed(
+            &spec.state_dir(program_name),
+            std::slice::from_ref(&spec.program_path),
+            &run_svc,
+            spec.health_check_enabled,
+            Duration::from_secs(spec.health_timeout),
+            backups_by_program.get(program_name).and_then(Option::as_ref),
+        )?;
+    }
+
+    log::debug!("✅ update complete - daemon reloaded, timers & runners active");
+    Ok(())
+}
 
-    log::debug!("4 - all unit files written");
+/// Run one program's update commands and (re)generate its systemd units.
+/// Does not reload systemd or enable/restart anything — the caller does
+/// that once, after every program has been processed. Returns the
+/// pre-update backups of its tracked paths, if health checking is enabled.
+fn update_program(
+    config_path: &PathBuf,
+    deploy_helper_exe: &Path,
+    sysd_dir: &Path,
+    program_name: &str,
+    spec: &ProgramSpec,
+) -> anyhow::Result<Option<Vec<(PathBuf, PathBuf)>>> {
+    // 1. Back up tracked paths before the update commands can overwrite them
+    let backups = if spec.health_check_enabled {
+        Some(state::backup_paths(
+            &spec.state_dir(program_name),
+            std::slice::from_ref(&spec.program_path),
+        )?)
+    } else {
+        None
+    };
 
-    // 5. Reload and enable units
-    Command::new("systemctl").arg("daemon-reload").status()?;
-    Command::new("systemctl")
-        .args(["enable", "--now", &update_timer])
-        .status()?;
-    restart_if_changed(&[config.binary_path], &run_svc)?;
+    // 2. Run the update commands, retrying transient failures
+    run_commands::run_commands_with_retry(
+        &spec.update.commands,
+        spec.update.max_retries,
+        Duration::from_secs(spec.update.base_delay),
+        Duration::from_secs(spec.update.max_delay),
+    )?;
+
+    // 3. Render and write unit files
+    for (name, contents) in units::render(config_path, deploy_helper_exe, program_name, spec) {
+        AtomicFile::new(&sysd_dir.join(&name), AllowOverwrite)
+            .write(|f| f.write_all(contents.as_bytes()))?;
+    }
 
-    log::debug!("✅ update complete - daemon reloaded, timer & runner active");
-    Ok(())
+    Ok(backups)
 }
 
-fn restart_if_changed(paths: &[PathBuf], run_svc: &str) -> anyhow::Result<()> {
-    use sha2::{Digest, Sha256};
-    use std::fs;
+/// Restart `run_svc` only if any of `paths` has a digest different from the
+/// one persisted in `state_dir` from the previous run. This makes the
+/// restart decision survive across process invocations and reboots, rather
+/// than comparing before/after digests within a single `update()` call.
+///
+/// When `health_check_enabled`, a restart is followed by polling
+/// `systemctl is-active`/`is-failed` for up to `health_timeout`; if the
+/// service hasn't come up healthy by then, `backups` are restored, the
+/// service is restarted again, and an error is returned describing the
+/// failed deploy. The fresh digests are only persisted once the restart is
+/// confirmed healthy (or health checking is disabled) — persisting them
+/// before that would make a rolled-back run look, on the next run, like the
+/// bad binary was already the deployed one, and silently skip restarting.
+fn restart_if_changed(
+    state_dir: &Path,
+    paths: &[PathBuf],
+    run_svc: &str,
+    health_check_enabled: bool,
+    health_timeout: Duration,
+    backups: Option<&Vec<(PathBuf, PathBuf)>>,
+) -> anyhow::Result<()> {
+    if !state::paths_changed(state_dir, paths)? {
+        log::info!("No tracked paths changed – skipping restart");
+        return Ok(());
+    }
 
-    fn digest(path: &PathBuf) -> anyhow::Result<Vec<u8>> {
-        let bytes = fs::read(path)?;
-        Ok(Sha256::digest(&bytes).to_vec())
+    Command::new("systemctl")
+        .args(["restart", run_svc])
+        .status()?;
+
+    if !health_check_enabled {
+        state::commit_digests(state_dir, paths)?;
+        return Ok(());
     }
 
-    let before: Vec<_> = paths.iter().map(digest).collect::<Result<_, _>>()?;
-    // …run update commands here…
-    let after: Vec<_> = paths.iter().map(digest).collect::<Result<_, _>>()?;
+    if health::wait_until_healthy(run_svc, health_timeout)? {
+        state::commit_digests(state_dir, paths)?;
+        return Ok(());
+    }
 
-    if before != after {
-        Command::new("systemctl")
-            .args(["restart", run_svc])
-            .status()?;
-    } else {
-        log::info!("No binaries changed – skipping restart");
+    log::error!(
+        "{} did not become healthy within {:?}; rolling back",
+        run_svc,
+        health_timeout
+    );
+    if let Some(backups) = backups {
+        state::restore_backups(backups)?;
     }
-    Ok(())
+    Command::new("systemctl")
+        .args(["restart", run_svc])
+        .status()?;
+    anyhow::bail!(
+        "deploy for `{}` failed its health check; rolled back tracked binaries",
+        run_svc
+    )
 }
@@ -0,0 +1,129 @@
+use crate::config::Config;
+use crate::units;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize)]
+pub struct UnitFile {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+#[derive(Serialize)]
+pub struct ProgramPlan {
+    pub update_commands: Vec<String>,
+    pub units: Vec<UnitFile>,
+    pub systemctl_commands: Vec<String>,
+    pub tracked_paths: Vec<PathBuf>,
+}
+
+#[derive(Serialize)]
+pub struct Plan {
+    pub programs: HashMap<String, ProgramPlan>,
+}
+
+/// Describe exactly what `update()` would do for `config`, without running
+/// any update command or writing any unit file.
+pub fn build(config_path: &Path, deploy_helper_exe: &Path, config: &Config) -> Plan {
+    let sysd_dir = units::sysd_dir();
+    let mut programs = HashMap::new();
+
+    for (program_name, spec) in &config.programs {
+        let update_timer = format!("update-{}.timer", program_name);
+        let run_svc = format!("run-{}.service", program_name);
+
+        let unit_files = units::render(config_path, deploy_helper_exe, program_name, spec)
+            .into_iter()
+            .map(|(name, contents)| UnitFile {
+                path: sysd_dir.join(name),
+                contents,
+            })
+            .collect();
+
+        let systemctl_commands = vec![
+            "daemon-reload".to_string(),
+            format!("enable --now {}", update_timer),
+            format!("restart {} (if tracked paths changed)", run_svc),
+        ];
+
+        programs.insert(
+            program_name.clone(),
+            ProgramPlan {
+                update_commands: spec.update.commands.clone(),
+                units: unit_files,
+                systemctl_commands,
+                tracked_paths: vec![spec.program_path.clone()],
+            },
+        );
+    }
+
+    Plan { programs }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ProgramSpec, Run, Update};
+
+    fn sample_config() -> Config {
+        let mut programs = HashMap::new();
+        programs.insert(
+            "geph".to_string(),
+            ProgramSpec {
+                program_path: PathBuf::from("/usr/bin/geph"),
+                update: Update {
+                    interval: 60,
+                    commands: vec!["git pull".to_string()],
+                    max_retries: 3,
+                    base_delay: 1,
+                    max_delay: 30,
+                },
+                run: Run {
+                    commands: vec!["./geph".to_string()],
+                },
+                state_directory: None,
+                health_check_enabled: true,
+                health_timeout: 30,
+            },
+        );
+        Config { programs }
+    }
+
+    #[test]
+    fn build_describes_every_program_without_touching_disk() {
+        let config = sample_config();
+        let plan = build(
+            Path::new("/etc/deploy-helper/config.yaml"),
+            Path::new("/usr/bin/deploy-helper"),
+            &config,
+        );
+
+        assert_eq!(plan.programs.len(), 1);
+        let program_plan = &plan.programs["geph"];
+        assert_eq!(program_plan.update_commands, vec!["git pull".to_string()]);
+        assert_eq!(
+            program_plan.tracked_paths,
+            vec![PathBuf::from("/usr/bin/geph")]
+        );
+        assert_eq!(program_plan.units.len(), 3);
+        assert!(program_plan.units.iter().any(|u| u
+            .path
+            .ends_with("update-geph.service")));
+        assert!(program_plan.units.iter().any(|u| u
+            .path
+            .ends_with("update-geph.timer")));
+        assert!(
+            program_plan
+                .units
+                .iter()
+                .any(|u| u.path.ends_with("run-geph.service"))
+        );
+        assert!(
+            program_plan
+                .systemctl_commands
+                .iter()
+                .any(|c| c == "daemon-reload")
+        );
+    }
+}
@@ -1,19 +1,177 @@
-use anyhow::Context;
-use std::process::Command;
+use anyhow::{Context, Result};
+use rand::Rng;
+use std::process::{Command, Output};
+use std::thread;
+use std::time::Duration;
 
-pub fn run_commands(commands: &[String]) -> anyhow::Result<()> {
+/// How a single command execution concluded.
+#[derive(Debug)]
+enum ExitOutcome {
+    Success,
+    NonZero(i32),
+    /// `status.code()` returned `None`: the process was killed by a signal
+    /// rather than exiting normally.
+    Signaled,
+}
+
+fn run_once(cmd: &str) -> Result<(ExitOutcome, Output)> {
+    log::debug!("Running: {}", cmd);
+    let output = Command::new("bash")
+        .arg("-c")
+        .arg(cmd)
+        .output()
+        .with_context(|| format!("failed to spawn bash for `{}`", cmd))?;
+
+    let outcome = match output.status.code() {
+        Some(0) => ExitOutcome::Success,
+        Some(code) => ExitOutcome::NonZero(code),
+        None => ExitOutcome::Signaled,
+    };
+    log::debug!("`{}` -> {:?}", cmd, outcome);
+    Ok((outcome, output))
+}
+
+/// Run a single command through `bash -ic`, inheriting stdio so it streams
+/// straight to the parent (and from there to journald under systemd) instead
+/// of being buffered in memory. Used for the long-lived `run` service
+/// command, which `.output()`'s unbounded in-memory capture is unsuitable
+/// for. `-i` sources interactive rc files, matching how these commands are
+/// normally invoked by hand (PATH/rustup/env set up there).
+fn run_streaming(cmd: &str) -> Result<ExitOutcome> {
+    log::debug!("Running (streaming): {}", cmd);
+    let status = Command::new("bash")
+        .arg("-ic")
+        .arg(cmd)
+        .status()
+        .with_context(|| format!("failed to spawn bash for `{}`", cmd))?;
+
+    let outcome = match status.code() {
+        Some(0) => ExitOutcome::Success,
+        Some(code) => ExitOutcome::NonZero(code),
+        None => ExitOutcome::Signaled,
+    };
+    log::debug!("`{}` -> {:?}", cmd, outcome);
+    Ok(outcome)
+}
+
+fn describe_exit(outcome: &ExitOutcome) -> String {
+    match outcome {
+        ExitOutcome::NonZero(code) => format!("exited with status {}", code),
+        ExitOutcome::Signaled => "was terminated by a signal".to_string(),
+        ExitOutcome::Success => unreachable!("describe_exit called on a successful outcome"),
+    }
+}
+
+fn describe_failure(cmd: &str, outcome: &ExitOutcome, output: &Output) -> String {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    format!(
+        "command `{}` {}\nstdout:\n{}\nstderr:\n{}",
+        cmd,
+        describe_exit(outcome),
+        stdout,
+        stderr
+    )
+}
+
+/// Run `commands` in order via [`run_streaming`]. Unlike the update path
+/// below, this doesn't buffer stdout/stderr: the `run` service command is
+/// long-lived, so capturing its output would grow without bound and
+/// prevent it from streaming live to journald under systemd.
+pub fn run_commands(commands: &[String]) -> Result<()> {
     for cmd in commands {
-        log::debug!("Running: {}", cmd);
-        let status = Command::new("bash")
-            .arg("-ic")
-            .arg(cmd)
-            .status()
-            .with_context(|| format!("Failed to spawn bash for `{}`", cmd))?;
-
-        if !status.success() {
-            anyhow::bail!("Command `{}` exited with status {}", cmd, status);
+        match run_streaming(cmd)? {
+            ExitOutcome::Success => {}
+            outcome => anyhow::bail!("command `{}` {}", cmd, describe_exit(&outcome)),
         }
     }
+    Ok(())
+}
 
+/// Run `commands` in order, retrying each one with exponential backoff
+/// (`base_delay * 2^attempt`, capped at `max_delay`, plus random jitter in
+/// `[0, delay/2)` to avoid a thundering herd across many hosts) on non-zero
+/// exit or signal termination. Gives up after `max_retries` retries.
+pub fn run_commands_with_retry(
+    commands: &[String],
+    max_retries: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+) -> Result<()> {
+    for cmd in commands {
+        run_with_retry(cmd, max_retries, base_delay, max_delay)?;
+    }
     Ok(())
 }
+
+/// `base_delay * 2^attempt`, capped at `max_delay`, before jitter.
+fn capped_delay(base_delay: Duration, max_delay: Duration, attempt: u32) -> Duration {
+    base_delay
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(max_delay)
+}
+
+fn run_with_retry(
+    cmd: &str,
+    max_retries: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        let (outcome, output) = run_once(cmd)?;
+        if let ExitOutcome::Success = outcome {
+            return Ok(());
+        }
+
+        if attempt >= max_retries {
+            anyhow::bail!(describe_failure(cmd, &outcome, &output));
+        }
+
+        let delay = capped_delay(base_delay, max_delay, attempt);
+        let jitter = Duration::from_secs_f64(
+            rand::thread_rng().gen_range(0.0..(delay.as_secs_f64() / 2.0).max(f64::EPSILON)),
+        );
+        let sleep_for = delay + jitter;
+        attempt += 1;
+        log::warn!(
+            "{} (attempt {}/{}), retrying in {:.1}s",
+            describe_failure(cmd, &outcome, &output),
+            attempt,
+            max_retries + 1,
+            sleep_for.as_secs_f64()
+        );
+        thread::sleep(sleep_for);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capped_delay_doubles_each_attempt() {
+        let base = Duration::from_secs(1);
+        let max = Duration::from_secs(1000);
+        assert_eq!(capped_delay(base, max, 0), Duration::from_secs(1));
+        assert_eq!(capped_delay(base, max, 1), Duration::from_secs(2));
+        assert_eq!(capped_delay(base, max, 2), Duration::from_secs(4));
+        assert_eq!(capped_delay(base, max, 3), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn capped_delay_is_bounded_by_max_delay() {
+        let base = Duration::from_secs(1);
+        let max = Duration::from_secs(10);
+        assert_eq!(capped_delay(base, max, 10), max);
+        assert_eq!(capped_delay(base, max, 63), max);
+    }
+
+    #[test]
+    fn capped_delay_never_overflows_on_large_attempt_counts() {
+        let base = Duration::from_secs(1);
+        let max = Duration::from_secs(30);
+        // `2u32.saturating_pow(attempt)` must saturate rather than panic.
+        assert_eq!(capped_delay(base, max, u32::MAX), max);
+    }
+}
@@ -0,0 +1,303 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Persisted record of the SHA-256 digests of tracked paths, keyed by path,
+/// so that restart decisions survive across process invocations and reboots.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    digests: HashMap<String, String>,
+}
+
+fn state_file_path(state_dir: &Path) -> PathBuf {
+    state_dir.join("state.yaml")
+}
+
+fn load_state(state_dir: &Path) -> State {
+    fs::read_to_string(state_file_path(state_dir))
+        .ok()
+        .and_then(|contents| serde_yaml::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Persist `state` to `state_dir` atomically: write to a temp file in the
+/// same directory with mode 0600, then `fs::rename` it into place, so a
+/// crash mid-write can never leave a corrupt state file behind.
+fn save_state(state_dir: &Path, state: &State) -> Result<()> {
+    fs::create_dir_all(state_dir)
+        .with_context(|| format!("failed to create state directory {}", state_dir.display()))?;
+
+    let final_path = state_file_path(state_dir);
+    let tmp_path = state_dir.join(format!(".state.yaml.{}.tmp", std::process::id()));
+    let serialized = serde_yaml::to_string(state)?;
+
+    let mut tmp_file = File::create(&tmp_path)
+        .with_context(|| format!("failed to create temp state file {}", tmp_path.display()))?;
+    tmp_file.write_all(serialized.as_bytes())?;
+    #[cfg(unix)]
+    {
+        let mut perms = tmp_file.metadata()?.permissions();
+        perms.set_mode(0o600);
+        tmp_file.set_permissions(perms)?;
+    }
+    drop(tmp_file);
+
+    fs::rename(&tmp_path, &final_path).with_context(|| {
+        format!(
+            "failed to rename temp state file into {}",
+            final_path.display()
+        )
+    })?;
+    Ok(())
+}
+
+fn digest(path: &Path) -> Result<String> {
+    let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(format!("{:x}", Sha256::digest(&bytes)))
+}
+
+/// How many timestamped backups to retain per tracked file name; older ones
+/// are pruned after a successful backup so `state_dir/backups` doesn't grow
+/// without bound across every update.
+const BACKUP_RETENTION: usize = 5;
+
+/// Copy each of `paths` into a timestamped backup under `state_dir/backups`,
+/// so they can be restored if the post-update health gate fails. Returns the
+/// (original, backup) pairs in the same order as `paths`, skipping any path
+/// that doesn't exist yet — on a fresh host the tracked binary may not exist
+/// until the update commands that follow have built or fetched it, and that
+/// shouldn't abort the update before it's had a chance to run.
+pub fn backup_paths(state_dir: &Path, paths: &[PathBuf]) -> Result<Vec<(PathBuf, PathBuf)>> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let backup_dir = state_dir.join("backups");
+    fs::create_dir_all(&backup_dir)
+        .with_context(|| format!("failed to create backup directory {}", backup_dir.display()))?;
+    // Nanosecond resolution (rather than seconds) so that backups taken in
+    // quick succession — as in a retry loop, or in tests — don't collide on
+    // the same file name and silently overwrite one another.
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+
+    let mut backups = Vec::new();
+    for path in paths {
+        if !path.exists() {
+            log::info!(
+                "tracked path {} does not exist yet; skipping pre-update backup",
+                path.display()
+            );
+            continue;
+        }
+
+        let file_name = path
+            .file_name()
+            .with_context(|| format!("tracked path {} has no file name", path.display()))?;
+        let backup_path =
+            backup_dir.join(format!("{}.{}.bak", file_name.to_string_lossy(), timestamp));
+        fs::copy(path, &backup_path).with_context(|| {
+            format!(
+                "failed to back up {} to {}",
+                path.display(),
+                backup_path.display()
+            )
+        })?;
+        prune_old_backups(&backup_dir, file_name).with_context(|| {
+            format!(
+                "failed to prune old backups of {} in {}",
+                file_name.to_string_lossy(),
+                backup_dir.display()
+            )
+        })?;
+        backups.push((path.clone(), backup_path));
+    }
+    Ok(backups)
+}
+
+/// Keep only the [`BACKUP_RETENTION`] most recent `{file_name}.*.bak` backups
+/// in `backup_dir`, removing older ones.
+fn prune_old_backups(backup_dir: &Path, file_name: &std::ffi::OsStr) -> Result<()> {
+    let prefix = format!("{}.", file_name.to_string_lossy());
+    let mut backups: Vec<PathBuf> = fs::read_dir(backup_dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(&prefix) && n.ends_with(".bak"))
+        })
+        .collect();
+    // File names embed the backup's unix timestamp, so lexicographic order
+    // is chronological order.
+    backups.sort();
+
+    if backups.len() > BACKUP_RETENTION {
+        for stale in &backups[..backups.len() - BACKUP_RETENTION] {
+            fs::remove_file(stale)
+                .with_context(|| format!("failed to remove stale backup {}", stale.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Restore each (original, backup) pair produced by [`backup_paths`].
+pub fn restore_backups(backups: &[(PathBuf, PathBuf)]) -> Result<()> {
+    for (original, backup) in backups {
+        fs::copy(backup, original).with_context(|| {
+            format!(
+                "failed to restore {} from backup {}",
+                original.display(),
+                backup.display()
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// Compute fresh digests for `paths` and compare them against the digests
+/// persisted from the previous run in `state_dir`. Does *not* persist
+/// anything; call [`commit_digests`] once the caller has confirmed the new
+/// digests describe what's actually running.
+pub fn paths_changed(state_dir: &Path, paths: &[PathBuf]) -> Result<bool> {
+    let previous = load_state(state_dir);
+    for path in paths {
+        let key = path.display().to_string();
+        if previous.digests.get(&key) != Some(&digest(path)?) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Persist the current digests of `paths` to `state_dir`, so future runs
+/// compare against what's now actually deployed. Only call this once the
+/// restart it followed has been confirmed healthy (or when health checking
+/// is disabled) — persisting on a rolled-back restart would make the next
+/// run believe the bad binary is already in place and skip restarting it.
+pub fn commit_digests(state_dir: &Path, paths: &[PathBuf]) -> Result<()> {
+    let mut fresh = State::default();
+    for path in paths {
+        let key = path.display().to_string();
+        fresh.digests.insert(key, digest(path)?);
+    }
+    save_state(state_dir, &fresh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh, unique scratch directory under the system temp dir, removed
+    /// when the guard is dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(tag: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "deploy-helper-test-{}-{}-{:?}",
+                std::process::id(),
+                tag,
+                std::thread::current().id(),
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn write_tracked_file(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn paths_changed_on_first_run() {
+        let scratch = TempDir::new("first-run");
+        let state_dir = scratch.path().join("state");
+        let tracked = write_tracked_file(scratch.path(), "bin", "v1");
+
+        // No state has ever been committed, so the first run must restart.
+        assert!(paths_changed(&state_dir, &[tracked]).unwrap());
+    }
+
+    #[test]
+    fn paths_unchanged_after_commit() {
+        let scratch = TempDir::new("unchanged");
+        let state_dir = scratch.path().join("state");
+        let tracked = write_tracked_file(scratch.path(), "bin", "v1");
+
+        commit_digests(&state_dir, std::slice::from_ref(&tracked)).unwrap();
+
+        assert!(!paths_changed(&state_dir, &[tracked]).unwrap());
+    }
+
+    #[test]
+    fn paths_changed_after_content_changes() {
+        let scratch = TempDir::new("changed");
+        let state_dir = scratch.path().join("state");
+        let tracked = write_tracked_file(scratch.path(), "bin", "v1");
+
+        commit_digests(&state_dir, std::slice::from_ref(&tracked)).unwrap();
+        fs::write(&tracked, "v2").unwrap();
+
+        assert!(paths_changed(&state_dir, &[tracked]).unwrap());
+    }
+
+    #[test]
+    fn backup_then_restore_roundtrips_original_contents() {
+        let scratch = TempDir::new("backup-restore");
+        let state_dir = scratch.path().join("state");
+        let tracked = write_tracked_file(scratch.path(), "bin", "good");
+
+        let backups = backup_paths(&state_dir, std::slice::from_ref(&tracked)).unwrap();
+        fs::write(&tracked, "bad").unwrap();
+        restore_backups(&backups).unwrap();
+
+        assert_eq!(fs::read_to_string(&tracked).unwrap(), "good");
+    }
+
+    #[test]
+    fn backup_paths_skips_a_tracked_path_that_does_not_exist_yet() {
+        let scratch = TempDir::new("missing-tracked-path");
+        let state_dir = scratch.path().join("state");
+        let not_yet_built = scratch.path().join("bin");
+
+        // On a fresh host the update commands build/fetch the tracked
+        // binary, so it won't exist before the first backup is taken.
+        let backups =
+            backup_paths(&state_dir, std::slice::from_ref(&not_yet_built)).unwrap();
+
+        assert!(backups.is_empty());
+    }
+
+    #[test]
+    fn backup_paths_prunes_backups_past_the_retention_limit() {
+        let scratch = TempDir::new("prune-backups");
+        let state_dir = scratch.path().join("state");
+        let tracked = write_tracked_file(scratch.path(), "bin", "v0");
+
+        for i in 0..(BACKUP_RETENTION + 3) {
+            fs::write(&tracked, format!("v{}", i)).unwrap();
+            backup_paths(&state_dir, std::slice::from_ref(&tracked)).unwrap();
+        }
+
+        let remaining = fs::read_dir(state_dir.join("backups")).unwrap().count();
+        assert_eq!(remaining, BACKUP_RETENTION);
+    }
+}
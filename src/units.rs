@@ -0,0 +1,76 @@
+use crate::config::ProgramSpec;
+use std::path::{Path, PathBuf};
+
+/// Names and rendered contents of the three systemd units a program needs:
+/// its update service, update timer, and run service, in that order.
+pub fn render(
+    config_path: &Path,
+    deploy_helper_exe: &Path,
+    program_name: &str,
+    spec: &ProgramSpec,
+) -> Vec<(String, String)> {
+    let update_svc = format!("update-{}.service", program_name);
+    let update_timer = format!("update-{}.timer", program_name);
+    let run_svc = format!("run-{}.service", program_name);
+
+    let update_unit = format!(
+        r#"[Unit]
+Description=deploy-helper update for {program_name}
+Wants={run_svc}
+After=network-online.target
+
+[Service]
+Type=oneshot
+ExecStart={deploy_helper_exe} update {config_path}
+"#,
+        program_name = program_name,
+        run_svc = run_svc,
+        deploy_helper_exe = deploy_helper_exe.display(),
+        config_path = config_path.display(),
+    );
+
+    let timer_unit = format!(
+        r#"[Unit]
+Description=deploy-helper update timer for {program_name}
+
+[Timer]
+OnBootSec=1min
+OnUnitActiveSec={interval}
+Unit={update_svc}
+
+[Install]
+WantedBy=timers.target
+"#,
+        program_name = program_name,
+        interval = spec.update.interval,
+        update_svc = update_svc,
+    );
+
+    let run_unit = format!(
+        r#"[Unit]
+Description=deploy-helper run for {program_name}
+
+[Service]
+Type=simple
+ExecStart={deploy_helper_exe} run {config_path} {program_name}
+Restart=on-failure
+RestartSec=5
+
+[Install]
+WantedBy=multi-user.target
+"#,
+        program_name = program_name,
+        deploy_helper_exe = deploy_helper_exe.display(),
+        config_path = config_path.display(),
+    );
+
+    vec![
+        (update_svc, update_unit),
+        (update_timer, timer_unit),
+        (run_svc, run_unit),
+    ]
+}
+
+pub fn sysd_dir() -> PathBuf {
+    PathBuf::from("/etc/systemd/system")
+}